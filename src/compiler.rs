@@ -0,0 +1,201 @@
+use crate::{Node, Spanned};
+use std::ops::Range;
+
+/// A single instruction for the stack machine in [`crate::vm`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instr {
+	NumPush(f64),
+	StrPush(String),
+	BoolPush(bool),
+	RangePush(i64, i64),
+	Get(String),
+	Set(String),
+	Call(String, usize),
+	Jump(usize),
+	JumpIfFalse(usize),
+	Pop,
+}
+
+/// A flat program: one instruction per slot, plus the source span that
+/// produced it so the VM can still report errors through `ariadne`.
+#[derive(Debug, Clone, Default)]
+pub struct Chunk {
+	pub instrs: Vec<Instr>,
+	pub spans: Vec<Range<usize>>,
+}
+
+impl Chunk {
+	fn emit(&mut self, instr: Instr, span: Range<usize>) -> usize {
+		self.instrs.push(instr);
+		self.spans.push(span);
+		self.instrs.len() - 1
+	}
+
+	fn len(&self) -> usize {
+		self.instrs.len()
+	}
+
+	fn patch_jump(&mut self, at: usize, target: usize) {
+		self.instrs[at] = match self.instrs[at] {
+			Instr::Jump(_) => Instr::Jump(target),
+			Instr::JumpIfFalse(_) => Instr::JumpIfFalse(target),
+			ref other => other.clone(),
+		};
+	}
+}
+
+/// Pending jump patches for the innermost `while` currently being
+/// compiled, so `break`/`continue` can be emitted before their targets
+/// (the loop's end, and its re-check or increment step) are known.
+#[derive(Default)]
+struct LoopCtx {
+	break_patches: Vec<usize>,
+	continue_patches: Vec<usize>,
+}
+
+/// Lower a parsed program into a flat instruction stream.
+pub fn compile(nodes: &[Spanned]) -> Chunk {
+	let mut chunk = Chunk::default();
+	let mut loops: Vec<LoopCtx> = Vec::new();
+
+	for node in nodes {
+		compile_node(node, &mut chunk, &mut loops);
+	}
+
+	chunk
+}
+
+fn compile_node(node: &Spanned, chunk: &mut Chunk, loops: &mut Vec<LoopCtx>) {
+	let span = node.1.clone();
+
+	match &node.0 {
+		Node::NumericLiteral(n) => {
+			chunk.emit(Instr::NumPush(*n), span);
+		}
+		Node::StringLiteral(s) => {
+			chunk.emit(Instr::StrPush(s.clone()), span);
+		}
+		Node::BooleanLiteral(b) => {
+			chunk.emit(Instr::BoolPush(*b), span);
+		}
+		Node::Range(start, end) => {
+			chunk.emit(Instr::RangePush(*start, *end), span);
+		}
+		Node::Ident(name) => {
+			chunk.emit(Instr::Get(name.clone()), span);
+		}
+		Node::Variable(name, value) => {
+			compile_node(value, chunk, loops);
+			chunk.emit(Instr::Set(name.clone()), span);
+		}
+		Node::Call(cname, args) => {
+			if let Node::Ident(name) = &cname.0 {
+				for arg in args {
+					compile_node(arg, chunk, loops);
+				}
+				chunk.emit(Instr::Call(name.clone(), args.len()), span);
+			}
+		}
+		Node::While(cond, body) => compile_while(cond, body, chunk, span, loops),
+		Node::Binary(op, lhs, rhs) => {
+			compile_node(lhs, chunk, loops);
+			compile_node(rhs, chunk, loops);
+			chunk.emit(Instr::Call(op.clone(), 2), span);
+		}
+		Node::Break => {
+			let at = chunk.emit(Instr::Jump(0), span);
+			if let Some(ctx) = loops.last_mut() {
+				ctx.break_patches.push(at);
+			}
+		}
+		Node::Continue => {
+			let at = chunk.emit(Instr::Jump(0), span);
+			if let Some(ctx) = loops.last_mut() {
+				ctx.continue_patches.push(at);
+			}
+		}
+		// User-defined functions and `return` are only supported by the
+		// tree-walking `eval` fallback so far; the bytecode backend has
+		// no call-stack yet, so definitions compile to a no-op.
+		Node::Function(_, _, _) | Node::Return(_) => {}
+	}
+}
+
+// `while range { ... }` is desugared into a hidden counter variable that
+// is compared and incremented with the `<`/`+` operators, so the VM
+// never needs a dedicated range-iteration instruction.
+fn compile_while(
+	cond: &Spanned,
+	body: &[Spanned],
+	chunk: &mut Chunk,
+	span: Range<usize>,
+	loops: &mut Vec<LoopCtx>,
+) {
+	if let Node::Range(start, end) = &cond.0 {
+		let counter = format!("__counter_{}", cond.1.start);
+
+		chunk.emit(Instr::NumPush(*start as f64), cond.1.clone());
+		chunk.emit(Instr::Set(counter.clone()), cond.1.clone());
+
+		let loop_start = chunk.len();
+		chunk.emit(Instr::Get(counter.clone()), cond.1.clone());
+		chunk.emit(Instr::NumPush(*end as f64), cond.1.clone());
+		chunk.emit(Instr::Call("<".to_string(), 2), cond.1.clone());
+		let jump_if_false = chunk.emit(Instr::JumpIfFalse(0), cond.1.clone());
+
+		loops.push(LoopCtx::default());
+
+		for node in body {
+			compile_node(node, chunk, loops);
+			chunk.emit(Instr::Pop, node.1.clone());
+		}
+
+		let ctx = loops.pop().expect("pushed above");
+
+		// `continue` still bumps the counter before re-checking, so it
+		// targets the increment step rather than `loop_start` directly.
+		let increment_at = chunk.len();
+		chunk.emit(Instr::Get(counter.clone()), cond.1.clone());
+		chunk.emit(Instr::NumPush(1.0), cond.1.clone());
+		chunk.emit(Instr::Call("+".to_string(), 2), cond.1.clone());
+		chunk.emit(Instr::Set(counter), cond.1.clone());
+		chunk.emit(Instr::Jump(loop_start), span);
+
+		let loop_end = chunk.len();
+		chunk.patch_jump(jump_if_false, loop_end);
+
+		for at in ctx.continue_patches {
+			chunk.patch_jump(at, increment_at);
+		}
+		for at in ctx.break_patches {
+			chunk.patch_jump(at, loop_end);
+		}
+
+		return;
+	}
+
+	let loop_start = chunk.len();
+	compile_node(cond, chunk, loops);
+	let jump_if_false = chunk.emit(Instr::JumpIfFalse(0), cond.1.clone());
+
+	loops.push(LoopCtx::default());
+
+	for node in body {
+		compile_node(node, chunk, loops);
+		chunk.emit(Instr::Pop, node.1.clone());
+	}
+
+	let ctx = loops.pop().expect("pushed above");
+
+	chunk.emit(Instr::Jump(loop_start), span);
+
+	let loop_end = chunk.len();
+	chunk.patch_jump(jump_if_false, loop_end);
+
+	for at in ctx.continue_patches {
+		chunk.patch_jump(at, loop_start);
+	}
+	for at in ctx.break_patches {
+		chunk.patch_jump(at, loop_end);
+	}
+}
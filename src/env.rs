@@ -0,0 +1,248 @@
+use crate::{EvaluationError, Spanned, Value};
+use std::collections::HashMap;
+
+/// A builtin registered in [`Env::natives`]. Receives the evaluated
+/// arguments and a mutable handle to the environment it was called from,
+/// so natives can read or write variables just like user code can.
+pub type NativeFn = fn(&mut Env, Vec<Value>) -> Result<Value, EvaluationError>;
+
+/// A user-defined function, as declared with `fn name(params) { body }`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserFunction {
+	pub params: Vec<String>,
+	pub body: Vec<Spanned>,
+}
+
+const DEFAULT_MAX_CALL_DEPTH: usize = 256;
+
+/// Runtime state threaded through `eval` and the VM: the variable
+/// bindings in scope, the table of native functions callers can extend
+/// before running a program, and any `fn` definitions collected while
+/// evaluating. Turns the interpreter into an embeddable library instead
+/// of a closed set of hardcoded builtins.
+pub struct Env {
+	pub variables: HashMap<String, Value>,
+	pub natives: HashMap<String, NativeFn>,
+	pub functions: HashMap<String, UserFunction>,
+	pub max_call_depth: usize,
+	scopes: Vec<HashMap<String, Value>>,
+}
+
+impl Default for Env {
+	fn default() -> Self {
+		Env {
+			variables: HashMap::new(),
+			natives: HashMap::new(),
+			functions: HashMap::new(),
+			max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+			scopes: Vec::new(),
+		}
+	}
+}
+
+impl Env {
+	pub fn new() -> Self {
+		Env::default()
+	}
+
+	pub fn register(&mut self, name: impl Into<String>, f: NativeFn) {
+		self.natives.insert(name.into(), f);
+	}
+
+	/// Bind a variable in the innermost function scope, or globally when
+	/// no function call is in progress.
+	pub fn define(&mut self, name: impl Into<String>, value: Value) {
+		match self.scopes.last_mut() {
+			Some(scope) => {
+				scope.insert(name.into(), value);
+			}
+			None => {
+				self.variables.insert(name.into(), value);
+			}
+		}
+	}
+
+	/// Look up a variable, preferring the innermost function scope over
+	/// globals so parameters shadow same-named globals.
+	pub fn get(&self, name: &str) -> Option<Value> {
+		self.scopes
+			.last()
+			.and_then(|scope| scope.get(name))
+			.or_else(|| self.variables.get(name))
+			.cloned()
+	}
+
+	pub fn push_scope(&mut self, scope: HashMap<String, Value>) {
+		self.scopes.push(scope);
+	}
+
+	pub fn pop_scope(&mut self) {
+		self.scopes.pop();
+	}
+
+	/// Number of nested user-function calls currently in progress.
+	pub fn call_depth(&self) -> usize {
+		self.scopes.len()
+	}
+}
+
+/// The default set of natives every `paris` program can rely on.
+/// Embedders start from this and call [`Env::register`] to add more.
+pub fn standard_env() -> Env {
+	let mut env = Env::new();
+
+	env.register("display", native_display);
+	env.register("len", native_len);
+	env.register("str", native_str);
+	env.register("num", native_num);
+
+	env.register("+", native_add);
+	env.register("-", native_sub);
+	env.register("*", native_mul);
+	env.register("/", native_div);
+	env.register("%", native_mod);
+	env.register("==", native_eq);
+	env.register("!=", native_neq);
+	env.register("<", native_lt);
+	env.register("<=", native_lte);
+	env.register(">", native_gt);
+	env.register(">=", native_gte);
+
+	env
+}
+
+fn native_display(_env: &mut Env, args: Vec<Value>) -> Result<Value, EvaluationError> {
+	let mut result = String::new();
+
+	for arg in &args {
+		result += &arg.to_string();
+	}
+
+	println!("{}", result);
+
+	Ok(Value::Null)
+}
+
+fn native_len(_env: &mut Env, args: Vec<Value>) -> Result<Value, EvaluationError> {
+	match args.get(0) {
+		Some(Value::String(s)) => Ok(Value::Number(s.chars().count() as f64)),
+		Some(Value::Range(start, end)) => Ok(Value::Number((end - start) as f64)),
+		_ => Ok(Value::Number(0.0)),
+	}
+}
+
+fn native_str(_env: &mut Env, args: Vec<Value>) -> Result<Value, EvaluationError> {
+	Ok(Value::String(
+		args.get(0).map(Value::to_string).unwrap_or_default(),
+	))
+}
+
+fn native_num(_env: &mut Env, args: Vec<Value>) -> Result<Value, EvaluationError> {
+	match args.get(0) {
+		Some(Value::Number(n)) => Ok(Value::Number(*n)),
+		Some(Value::String(s)) => Ok(Value::Number(s.parse().unwrap_or(0.0))),
+		Some(Value::Boolean(b)) => Ok(Value::Number(if *b { 1.0 } else { 0.0 })),
+		_ => Ok(Value::Number(0.0)),
+	}
+}
+
+fn expect_numbers(op: &str, args: &[Value]) -> Result<(f64, f64), EvaluationError> {
+	match (args.get(0), args.get(1)) {
+		(Some(Value::Number(a)), Some(Value::Number(b))) => Ok((*a, *b)),
+		_ => Err(EvaluationError::TypeMismatch(format!(
+			"operator `{}` expects two numbers",
+			op
+		))),
+	}
+}
+
+fn native_add(_env: &mut Env, args: Vec<Value>) -> Result<Value, EvaluationError> {
+	let (a, b) = expect_numbers("+", &args)?;
+	Ok(Value::Number(a + b))
+}
+
+fn native_sub(_env: &mut Env, args: Vec<Value>) -> Result<Value, EvaluationError> {
+	let (a, b) = expect_numbers("-", &args)?;
+	Ok(Value::Number(a - b))
+}
+
+fn native_mul(_env: &mut Env, args: Vec<Value>) -> Result<Value, EvaluationError> {
+	let (a, b) = expect_numbers("*", &args)?;
+	Ok(Value::Number(a * b))
+}
+
+fn native_div(_env: &mut Env, args: Vec<Value>) -> Result<Value, EvaluationError> {
+	let (a, b) = expect_numbers("/", &args)?;
+	Ok(Value::Number(a / b))
+}
+
+fn native_mod(_env: &mut Env, args: Vec<Value>) -> Result<Value, EvaluationError> {
+	let (a, b) = expect_numbers("%", &args)?;
+	Ok(Value::Number(a % b))
+}
+
+fn native_eq(_env: &mut Env, args: Vec<Value>) -> Result<Value, EvaluationError> {
+	Ok(Value::Boolean(args.get(0) == args.get(1)))
+}
+
+fn native_neq(_env: &mut Env, args: Vec<Value>) -> Result<Value, EvaluationError> {
+	Ok(Value::Boolean(args.get(0) != args.get(1)))
+}
+
+fn native_lt(_env: &mut Env, args: Vec<Value>) -> Result<Value, EvaluationError> {
+	let (a, b) = expect_numbers("<", &args)?;
+	Ok(Value::Boolean(a < b))
+}
+
+fn native_lte(_env: &mut Env, args: Vec<Value>) -> Result<Value, EvaluationError> {
+	let (a, b) = expect_numbers("<=", &args)?;
+	Ok(Value::Boolean(a <= b))
+}
+
+fn native_gt(_env: &mut Env, args: Vec<Value>) -> Result<Value, EvaluationError> {
+	let (a, b) = expect_numbers(">", &args)?;
+	Ok(Value::Boolean(a > b))
+}
+
+fn native_gte(_env: &mut Env, args: Vec<Value>) -> Result<Value, EvaluationError> {
+	let (a, b) = expect_numbers(">=", &args)?;
+	Ok(Value::Boolean(a >= b))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn standard_env_registers_the_arithmetic_and_comparison_natives() {
+		let env = standard_env();
+
+		for name in ["display", "len", "str", "num", "+", "-", "*", "/", "%", "==", "!=", "<", "<=", ">", ">="] {
+			assert!(env.natives.contains_key(name), "missing native `{}`", name);
+		}
+	}
+
+	#[test]
+	fn define_without_a_scope_binds_a_global() {
+		let mut env = Env::new();
+		env.define("x", Value::Number(1.0));
+
+		assert_eq!(env.get("x"), Some(Value::Number(1.0)));
+	}
+
+	#[test]
+	fn pushed_scope_shadows_a_same_named_global() {
+		let mut env = Env::new();
+		env.define("x", Value::Number(1.0));
+
+		let mut scope = HashMap::new();
+		scope.insert("x".to_string(), Value::Number(2.0));
+		env.push_scope(scope);
+
+		assert_eq!(env.get("x"), Some(Value::Number(2.0)));
+		assert_eq!(env.call_depth(), 1);
+
+		env.pop_scope();
+		assert_eq!(env.get("x"), Some(Value::Number(1.0)));
+	}
+}
@@ -3,6 +3,13 @@ use chumsky::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, ops::Range};
 
+pub mod compiler;
+pub mod env;
+pub mod typecheck;
+pub mod vm;
+
+pub use env::{standard_env, Env, NativeFn, UserFunction};
+
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub enum Value {
 	Null,
@@ -30,11 +37,15 @@ pub enum Node {
 	StringLiteral(String),
 	BooleanLiteral(bool),
 	Ident(String),
-	Op(String),
 	Call(Box<Spanned>, Vec<Spanned>),
 	While(Box<Spanned>, Vec<Spanned>),
 	Range(i64, i64),
 	Variable(String, Box<Spanned>),
+	Binary(String, Box<Spanned>, Box<Spanned>),
+	Function(String, Vec<String>, Vec<Spanned>),
+	Return(Box<Spanned>),
+	Break,
+	Continue,
 }
 
 pub type Spanned = (Node, Range<usize>);
@@ -83,42 +94,93 @@ pub fn lexer() -> impl Parser<char, Vec<Spanned>, Error = Simple<char>> {
 		.or(just("false"))
 		.map(|b| Node::BooleanLiteral(b == "true"));
 
-	let ident = text::ident()
-		.labelled("identifier")
-		.map_with_span(|ident, span| (Node::Ident(ident), span));
+	// Reserved words that introduce a statement form of their own, so
+	// they can never double as a plain identifier. Without this,
+	// `text::ident()` would happily parse `while`/`fn`/etc. as a bare
+	// call name, letting a malformed keyword-led statement fall through
+	// to an ordinary expression instead of surfacing its real parse error.
+	const KEYWORDS: [&str; 5] = ["while", "fn", "return", "break", "continue"];
 
-	let op = one_of("=.:%,")
-		.repeated()
-		.at_least(1)
-		.collect()
-		.labelled("operator")
-		.map(Node::Op);
+	let raw_ident = text::ident().try_map(|name: String, span| {
+		if KEYWORDS.contains(&name.as_str()) {
+			Err(Simple::custom(span, format!("`{}` is a reserved word", name)))
+		} else {
+			Ok(name)
+		}
+	});
+
+	// Two-character operators are tried before their single-character
+	// prefixes (`<=` before `<`) so the longer match wins.
+	let infix_op = just("==")
+		.or(just("!="))
+		.or(just("<="))
+		.or(just(">="))
+		.or(just("<"))
+		.or(just(">"))
+		.or(just("+"))
+		.or(just("-"))
+		.or(just("*"))
+		.or(just("/"))
+		.or(just("%"))
+		.map(|op: &str| op.to_string())
+		.labelled("infix operator");
 
 	let tt = recursive(|tt| {
 		let tt_span = tt.clone().padded().map_with_span(|n, span| (n, span));
 
-		let func_call = text::ident()
-			.map_with_span(|name, span| (Node::Ident(name), span))
-			.then(
-				ident
-					.or(tt_span.clone())
-					.padded()
-					.separated_by(just(','))
-					.allow_trailing(),
-			)
-			.labelled("function call")
-			.map(|(name, args)| Node::Call(Box::new(name), args));
+		// Call arguments are only ever expressions, never a full
+		// statement — so this is its own recursion, separate from `tt`.
+		// Letting a call's args fall through to statement forms (as
+		// `tt_span` does) meant a bare identifier ending one statement
+		// (`result := a`) could swallow an entire following statement
+		// (`return result`) as its "argument".
+		let expr = recursive(|expr| {
+			let arg = expr.clone().map_with_span(|n, span| (n, span)).padded();
+
+			let func_call = raw_ident
+				.clone()
+				.map_with_span(|name, span| (Node::Ident(name), span))
+				.then(arg.separated_by(just(',')).allow_trailing())
+				.labelled("function call")
+				.map(|(name, args)| {
+					// A call with no arguments is indistinguishable from a bare
+					// identifier, so treat it as a variable reference instead.
+					if args.is_empty() {
+						name.0
+					} else {
+						Node::Call(Box::new(name), args)
+					}
+				});
+
+			let primary = boolean
+				.or(string)
+				.or(range)
+				.or(number)
+				.or(func_call)
+				.map_with_span(|n, span| (n, span));
 
+			primary
+				.clone()
+				.then(infix_op.padded().then(primary).repeated())
+				.map(|(first, rest)| fold_infix(first, rest))
+		});
+
+		// Two statements in a block must be separated by an explicit
+		// `.`/`;` — without that, a bare identifier ending one statement
+		// (`x := i`) is indistinguishable from the start of a no-paren
+		// call whose first argument is the next statement (`i := i + 1`),
+		// silently swallowing it instead of erroring.
 		let block = tt_span
 			.clone()
 			.padded()
-			.then_ignore(just('.').or(just(';')).or_not())
-			.repeated()
+			.separated_by(just('.').or(just(';')).padded())
+			.allow_trailing()
 			.or_not()
 			.delimited_by(just('{'), just('}'))
 			.labelled("block");
 
-		let variable = text::ident()
+		let variable = raw_ident
+			.clone()
 			.padded()
 			.then_ignore(just(":=").padded())
 			.then(tt_span.clone().padded())
@@ -127,22 +189,53 @@ pub fn lexer() -> impl Parser<char, Vec<Spanned>, Error = Simple<char>> {
 
 		let while_loop = just("while")
 			.padded()
-			.ignore_then(tt_span)
-			.then(block)
+			.ignore_then(tt_span.clone())
+			.then(block.clone())
 			.padded()
 			.labelled("while loop")
 			.map(|(condition, body)| {
 				Node::While(Box::new(condition), body.unwrap_or_default())
 			});
 
+		let params = raw_ident
+			.clone()
+			.padded()
+			.separated_by(just(','))
+			.allow_trailing()
+			.delimited_by(just('('), just(')'))
+			// Without this, whitespace between the closing `)` and the
+			// body's `{` (e.g. a function def split across two lines)
+			// is never consumed, so `block` fails to find its opening
+			// brace right where it starts.
+			.padded();
+
+		let function_def = just("fn")
+			.padded()
+			.ignore_then(raw_ident.clone())
+			.then(params)
+			.then(block)
+			.padded()
+			.labelled("function definition")
+			.map(|((name, params), body)| {
+				Node::Function(name, params, body.unwrap_or_default())
+			});
+
+		let return_stmt = just("return")
+			.padded()
+			.ignore_then(tt_span)
+			.labelled("return")
+			.map(|value| Node::Return(Box::new(value)));
+
+		let break_stmt = just("break").map(|_| Node::Break);
+		let continue_stmt = just("continue").map(|_| Node::Continue);
+
 		while_loop
-			.or(boolean)
-			.or(string)
-			.or(range)
-			.or(number)
+			.or(function_def)
+			.or(return_stmt)
+			.or(break_stmt)
+			.or(continue_stmt)
 			.or(variable)
-			.or(op)
-			.or(func_call)
+			.or(expr)
 	})
 	.map_with_span(|n, span| (n, span));
 
@@ -152,6 +245,49 @@ pub fn lexer() -> impl Parser<char, Vec<Spanned>, Error = Simple<char>> {
 		.then_ignore(end())
 }
 
+fn precedence(op: &str) -> u8 {
+	match op {
+		"*" | "/" | "%" => 3,
+		"+" | "-" => 2,
+		"<" | "<=" | ">" | ">=" => 1,
+		_ => 0, // "==", "!="
+	}
+}
+
+fn reduce_top(operands: &mut Vec<Spanned>, operators: &mut Vec<String>) {
+	let op = operators.pop().expect("operator stack is non-empty");
+	let rhs = operands.pop().expect("rhs operand is present");
+	let lhs = operands.pop().expect("lhs operand is present");
+	let span = lhs.1.start..rhs.1.end;
+
+	operands.push((Node::Binary(op, Box::new(lhs), Box::new(rhs)), span));
+}
+
+// Shunting-yard: fold a primary followed by zero or more `(op, primary)`
+// pairs into a single expression tree, respecting operator precedence.
+fn fold_infix(first: Spanned, rest: Vec<(String, Spanned)>) -> Node {
+	let mut operands = vec![first];
+	let mut operators: Vec<String> = Vec::new();
+
+	for (op, rhs) in rest {
+		while operators
+			.last()
+			.map_or(false, |top| precedence(top) >= precedence(&op))
+		{
+			reduce_top(&mut operands, &mut operators);
+		}
+
+		operators.push(op);
+		operands.push(rhs);
+	}
+
+	while !operators.is_empty() {
+		reduce_top(&mut operands, &mut operators);
+	}
+
+	operands.pop().expect("at least one operand").0
+}
+
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -160,91 +296,335 @@ pub enum EvaluationError {
 	FunctionNotFound(String),
 	#[error("variable {0} not found")]
 	VariableNotFound(String),
+	#[error("{0}")]
+	TypeMismatch(String),
+	#[error("stack overflow while calling {0}")]
+	StackOverflow(String),
+	#[error("{0} expects {1} argument(s), found {2}")]
+	ArityMismatch(String, usize, usize),
 }
 
 pub type SpannedEvaluationError = (EvaluationError, Range<usize>);
 
+/// Whether a statement completed with an ordinary value, or unwound one
+/// or more enclosing constructs early via `return`, `break`, or
+/// `continue`.
+enum Flow {
+	Value(Value),
+	Return(Value),
+	Break,
+	Continue,
+}
+
+impl Flow {
+	fn into_value(self) -> Value {
+		match self {
+			Flow::Value(v) | Flow::Return(v) => v,
+			Flow::Break | Flow::Continue => Value::Null,
+		}
+	}
+}
+
+fn truthy(value: &Value) -> bool {
+	match value {
+		Value::Boolean(b) => *b,
+		Value::Number(n) => *n > 0.0,
+		Value::Range(start, end) => start < end,
+		Value::String(s) => !s.is_empty(),
+		Value::Null => false,
+	}
+}
+
+// Tree-walking fallback interpreter, kept for callers that evaluate an
+// AST directly instead of going through `compiler::compile` + `vm::Vm`.
 pub fn eval(
 	source: &Source,
 	node: &Spanned,
-	variables: &mut HashMap<String, Value>,
+	env: &mut Env,
 ) -> Result<Value, SpannedEvaluationError> {
+	eval_flow(source, node, env).map(Flow::into_value)
+}
+
+fn eval_flow(
+	source: &Source,
+	node: &Spanned,
+	env: &mut Env,
+) -> Result<Flow, SpannedEvaluationError> {
 	match &node.0 {
 		Node::Call(cname, args) => {
 			if let Node::Ident(name) = cname.0.clone() {
-				if name.as_str() == "display" {
-					let mut result = String::new();
-
-					for arg in args {
-						let value = eval(source, arg, variables)?;
+				if let Some(function) = env.functions.get(&name).cloned() {
+					return call_function(source, &name, &function, args, &node.1, env)
+						.map(Flow::Value);
+				}
 
-						result += &value.to_string();
-					}
+				let native = *env.natives.get(&name).ok_or((
+					EvaluationError::FunctionNotFound(name),
+					cname.1.clone(),
+				))?;
 
-					println!("{}", result);
-				} else {
-					return Err((
-						EvaluationError::FunctionNotFound(name),
-						cname.1.clone(),
-					));
+				let mut values = Vec::with_capacity(args.len());
+				for arg in args {
+					values.push(eval(source, arg, env)?);
 				}
+
+				return native(env, values)
+					.map(Flow::Value)
+					.map_err(|e| (e, node.1.clone()));
 			}
 		}
-		Node::StringLiteral(s) => return Ok(Value::String(s.clone())),
-		Node::NumericLiteral(n) => return Ok(Value::Number(*n)),
-		Node::BooleanLiteral(b) => return Ok(Value::Boolean(*b)),
-		Node::Range(start, end) => return Ok(Value::Range(*start, *end)),
+		Node::StringLiteral(s) => return Ok(Flow::Value(Value::String(s.clone()))),
+		Node::NumericLiteral(n) => return Ok(Flow::Value(Value::Number(*n))),
+		Node::BooleanLiteral(b) => return Ok(Flow::Value(Value::Boolean(*b))),
+		Node::Range(start, end) => return Ok(Flow::Value(Value::Range(*start, *end))),
+		Node::Binary(op, lhs, rhs) => {
+			let lhs = eval(source, lhs, env)?;
+			let rhs = eval(source, rhs, env)?;
+
+			let native = *env.natives.get(op.as_str()).ok_or((
+				EvaluationError::FunctionNotFound(op.clone()),
+				node.1.clone(),
+			))?;
+
+			return native(env, vec![lhs, rhs])
+				.map(Flow::Value)
+				.map_err(|e| (e, node.1.clone()));
+		}
 		Node::While(cond, body) => {
-			let condition = eval(source, cond, variables)?;
-
-			match condition {
-				Value::Number(n) => {
-					if n > 0.0 {
-						loop {
-							for node in body {
-								eval(source, node, variables)?;
-							}
-						}
+			// `while` re-checks its condition at the top of every
+			// iteration. A literal range condition is desugared into a
+			// hidden counter variable so it still bounds the loop instead
+			// of being re-evaluated as the same constant forever.
+			if let Node::Range(start, end) = &cond.0 {
+				let counter = format!("__counter_{}", cond.1.start);
+				env.define(counter.clone(), Value::Number(*start as f64));
+
+				loop {
+					let current = match env.get(&counter) {
+						Some(Value::Number(n)) => n,
+						_ => break,
+					};
+
+					if !(current < *end as f64) {
+						break;
 					}
-				}
-				Value::Boolean(bool) => {
-					if bool {
-						loop {
-							for node in body {
-								eval(source, node, variables)?;
-							}
-						}
+
+					match run_loop_body(source, body, env)? {
+						LoopSignal::Return(v) => return Ok(Flow::Return(v)),
+						LoopSignal::Break => break,
+						LoopSignal::Continue => {}
 					}
+
+					env.define(counter.clone(), Value::Number(current + 1.0));
 				}
-				Value::Range(start, end) => {
-					for _ in start..end {
-						for node in body {
-							eval(source, node, variables)?;
-						}
+			} else {
+				loop {
+					if !truthy(&eval(source, cond, env)?) {
+						break;
+					}
+
+					match run_loop_body(source, body, env)? {
+						LoopSignal::Return(v) => return Ok(Flow::Return(v)),
+						LoopSignal::Break => break,
+						LoopSignal::Continue => {}
 					}
 				}
-				_ => {}
 			}
 		}
+		Node::Break => return Ok(Flow::Break),
+		Node::Continue => return Ok(Flow::Continue),
 		Node::Variable(name, value) => {
-			let val = eval(source, value, variables)?;
+			let val = eval(source, value, env)?;
 
-			variables.insert(name.to_string(), val);
+			env.define(name.to_string(), val);
 		}
 		Node::Ident(ident) => {
-			let var = variables.get(ident);
+			if let Some(value) = env.get(ident) {
+				return Ok(Flow::Value(value));
+			}
 
-			if let Some(var) = var {
-				return Ok(var.clone());
-			} else {
-				return Err((
-					EvaluationError::VariableNotFound(ident.to_string()),
-					node.1.clone(),
-				));
+			// A zero-argument call is indistinguishable from a bare
+			// identifier at parse time (see `func_call`), so a name that
+			// isn't a variable gets one more chance as a no-arg function.
+			if let Some(function) = env.functions.get(ident).cloned() {
+				return call_function(source, ident, &function, &[], &node.1, env)
+					.map(Flow::Value);
 			}
+
+			return Err((
+				EvaluationError::VariableNotFound(ident.to_string()),
+				node.1.clone(),
+			));
+		}
+		Node::Function(name, params, body) => {
+			env.functions.insert(
+				name.clone(),
+				UserFunction {
+					params: params.clone(),
+					body: body.clone(),
+				},
+			);
 		}
-		n => panic!("not implemented: {:?}", n),
+		Node::Return(value) => {
+			let val = eval(source, value, env)?;
+			return Ok(Flow::Return(val));
+		}
+	}
+
+	Ok(Flow::Value(Value::Null))
+}
+
+/// What a `while` body did on one pass, collapsing per-statement `Flow`
+/// into the three things a loop cares about.
+enum LoopSignal {
+	Continue,
+	Break,
+	Return(Value),
+}
+
+fn run_loop_body(
+	source: &Source,
+	body: &[Spanned],
+	env: &mut Env,
+) -> Result<LoopSignal, SpannedEvaluationError> {
+	for stmt in body {
+		match eval_flow(source, stmt, env)? {
+			Flow::Return(v) => return Ok(LoopSignal::Return(v)),
+			Flow::Break => return Ok(LoopSignal::Break),
+			Flow::Continue => return Ok(LoopSignal::Continue),
+			Flow::Value(_) => {}
+		}
+	}
+
+	Ok(LoopSignal::Continue)
+}
+
+fn call_function(
+	source: &Source,
+	name: &str,
+	function: &UserFunction,
+	args: &[Spanned],
+	span: &Range<usize>,
+	env: &mut Env,
+) -> Result<Value, SpannedEvaluationError> {
+	if args.len() != function.params.len() {
+		return Err((
+			EvaluationError::ArityMismatch(
+				name.to_string(),
+				function.params.len(),
+				args.len(),
+			),
+			span.clone(),
+		));
+	}
+
+	if env.call_depth() >= env.max_call_depth {
+		return Err((
+			EvaluationError::StackOverflow(name.to_string()),
+			span.clone(),
+		));
+	}
+
+	let mut values = Vec::with_capacity(args.len());
+	for arg in args {
+		values.push(eval(source, arg, env)?);
 	}
 
-	Ok(Value::Null)
+	let mut scope = HashMap::new();
+	for (param, value) in function.params.iter().zip(values) {
+		scope.insert(param.clone(), value);
+	}
+
+	env.push_scope(scope);
+
+	let mut result = Value::Null;
+	for stmt in &function.body {
+		match eval_flow(source, stmt, env) {
+			Ok(Flow::Value(v)) => result = v,
+			Ok(Flow::Return(v)) => {
+				result = v;
+				break;
+			}
+			// A stray `break`/`continue` outside a loop has no effect.
+			Ok(Flow::Break) | Ok(Flow::Continue) => {}
+			Err(e) => {
+				env.pop_scope();
+				return Err(e);
+			}
+		}
+	}
+
+	env.pop_scope();
+
+	Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use chumsky::Parser;
+
+	fn run(src: &str) -> Value {
+		let ast = lexer().parse(src).expect("parse error");
+		let source = Source::from(src);
+		let mut env = standard_env();
+
+		let mut result = Value::Null;
+		for node in &ast {
+			result = eval(&source, node, &mut env).expect("runtime error");
+		}
+
+		result
+	}
+
+	#[test]
+	fn calls_a_multi_statement_function_with_params() {
+		let result = run(
+			"fn add(a, b) {
+				total := a + b;
+				return total
+			}
+			add 1, 2",
+		);
+
+		assert_eq!(result, Value::Number(3.0));
+	}
+
+	#[test]
+	fn unconditional_self_recursion_hits_the_call_depth_limit() {
+		let ast = lexer()
+			.parse("fn loop_forever(n) { return loop_forever n }\nloop_forever 0")
+			.expect("parse error");
+		let source = Source::from("");
+		let mut env = standard_env();
+
+		let mut last = Ok(Value::Null);
+		for node in &ast {
+			last = eval(&source, node, &mut env);
+		}
+
+		assert!(matches!(
+			last,
+			Err((EvaluationError::StackOverflow(..), _))
+		));
+	}
+
+	#[test]
+	fn arity_mismatch_is_a_runtime_error_not_a_panic() {
+		let ast = lexer()
+			.parse("fn add(a, b) { return a + b }\nadd(1)")
+			.expect("parse error");
+		let source = Source::from("");
+		let mut env = standard_env();
+
+		let mut last = Ok(Value::Null);
+		for node in &ast {
+			last = eval(&source, node, &mut env);
+		}
+
+		assert!(matches!(
+			last,
+			Err((EvaluationError::ArityMismatch(..), _))
+		));
+	}
 }
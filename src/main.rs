@@ -1,61 +1,175 @@
 use ariadne::{Color, Fmt, Label, Report, ReportKind, Source};
-use chumsky::{prelude::Simple, Parser};
-use paris_lang::{eval, lexer, Value};
-use std::{collections::HashMap, env, fs};
+use chumsky::{
+	error::SimpleReason,
+	prelude::Simple,
+	Parser,
+};
+use paris_lang::{
+	compiler, eval, lexer,
+	standard_env,
+	typecheck::{typecheck, typecheck_with, Type},
+	vm::Vm,
+	Env, Node, Spanned, SpannedEvaluationError, Value,
+};
+use std::{
+	collections::HashMap,
+	env, fs,
+	io::{self, Write},
+};
 
 fn main() {
-	let src =
-		fs::read_to_string(env::args().nth(1).expect("Expected file argument"))
-			.expect("Failed to read file");
+	match env::args().nth(1) {
+		Some(path) => run_file(&path),
+		None => repl(),
+	}
+}
 
+fn run_file(path: &str) {
+	let src = fs::read_to_string(path).expect("Failed to read file");
 	let (ast, mut errs) = lexer().parse_recovery(src.as_str());
-	let mut variables: HashMap<String, Value> = HashMap::new();
 
 	if let Some(ast) = ast.as_ref() {
 		if cfg!(debug_assertions) {
 			dbg!(ast);
 		}
 
-		for node in ast {
-			match eval(&src.clone().into(), node, &mut variables) {
+		let type_errors = typecheck(ast);
+
+		if type_errors.is_empty() {
+			let source = Source::from(&src);
+			let mut env = standard_env();
+
+			match run_program(&source, ast, &mut env) {
 				Ok(val) => print!("{}", val),
 				Err(e) => errs.push(Simple::custom(e.1, e.0)),
 			}
+		} else {
+			errs.extend(type_errors);
+		}
+	}
+
+	report_errors(&src, errs);
+}
+
+// A REPL for experimenting with the language without a file: each line is
+// parsed on its own, accumulating into `buffer` across lines until it no
+// longer ends with an unclosed `{`, so blocks and while-loops can span
+// several lines. Variables and function definitions persist across
+// evaluations by reusing the same `Env` for the whole session.
+fn repl() {
+	let mut env = standard_env();
+	let mut types: HashMap<String, Type> = HashMap::new();
+	let mut buffer = String::new();
+
+	loop {
+		print!("{}", if buffer.is_empty() { "> " } else { "... " });
+		io::stdout().flush().ok();
+
+		let mut line = String::new();
+		if io::stdin().read_line(&mut line).unwrap_or(0) == 0 {
+			break;
+		}
+
+		buffer.push_str(&line);
+
+		let (ast, errs) = lexer().parse_recovery(buffer.as_str());
+
+		if unclosed_block(&errs) {
+			continue;
+		}
+
+		if let Some(ast) = ast.as_ref() {
+			let type_errors = typecheck_with(ast, &mut types);
+
+			if type_errors.is_empty() {
+				let source = Source::from(&buffer);
+
+				for node in ast {
+					match run_program(&source, std::slice::from_ref(node), &mut env) {
+						Ok(val) => println!("{}", val),
+						Err(e) => report_errors(&buffer, vec![Simple::custom(e.1, e.0)]),
+					}
+				}
+			} else {
+				report_errors(&buffer, type_errors);
+			}
+		}
+
+		if !errs.is_empty() {
+			report_errors(&buffer, errs);
+		}
+
+		buffer.clear();
+	}
+}
+
+// The bytecode VM has no call stack, so it can't run a program that
+// defines or calls a user function — those fall back to the
+// tree-walking `eval`, which already supports them. Everything else
+// still goes through `compiler::compile` + `Vm` for speed.
+fn run_program(
+	source: &Source,
+	nodes: &[Spanned],
+	env: &mut Env,
+) -> Result<Value, SpannedEvaluationError> {
+	let needs_eval = !env.functions.is_empty()
+		|| nodes.iter().any(|n| matches!(n.0, Node::Function(..)));
+
+	if needs_eval {
+		let mut result = Value::Null;
+		for node in nodes {
+			result = eval(source, node, env)?;
 		}
+		Ok(result)
+	} else {
+		let chunk = compiler::compile(nodes);
+		let mut vm = Vm::with_env(std::mem::take(env));
+		let result = vm.run(&chunk);
+		*env = vm.env;
+		result
 	}
+}
+
+fn unclosed_block(errs: &[Simple<char>]) -> bool {
+	errs.iter().any(|e| {
+		matches!(
+			e.reason(),
+			SimpleReason::Unclosed { delimiter, .. } if *delimiter == '{'
+		)
+	})
+}
 
+fn report_errors(src: &str, errs: Vec<Simple<char>>) {
 	errs.into_iter()
 		.map(|e| e.map(|c| c.to_string()))
 		.for_each(|e| {
 			let report = Report::build(ReportKind::Error, (), e.span().start);
 
 			let report = match e.reason() {
-				chumsky::error::SimpleReason::Unclosed { span, delimiter } => {
-					report
-						.with_message(format!(
-							"Unclosed delimiter {}",
-							delimiter.fg(Color::Yellow)
-						))
-						.with_label(
-							Label::new(span.clone())
-								.with_message(format!(
-									"Unclosed delimiter {}",
-									delimiter.fg(Color::Yellow)
-								))
-								.with_color(Color::Yellow),
-						)
-						.with_label(
-							Label::new(e.span())
-								.with_message(format!(
-									"Must be closed before this {}",
-									e.found()
-										.unwrap_or(&"end of file".to_string())
-										.fg(Color::Red)
-								))
-								.with_color(Color::Red),
-						)
-				}
-				chumsky::error::SimpleReason::Unexpected => report
+				SimpleReason::Unclosed { span, delimiter } => report
+					.with_message(format!(
+						"Unclosed delimiter {}",
+						delimiter.fg(Color::Yellow)
+					))
+					.with_label(
+						Label::new(span.clone())
+							.with_message(format!(
+								"Unclosed delimiter {}",
+								delimiter.fg(Color::Yellow)
+							))
+							.with_color(Color::Yellow),
+					)
+					.with_label(
+						Label::new(e.span())
+							.with_message(format!(
+								"Must be closed before this {}",
+								e.found()
+									.unwrap_or(&"end of file".to_string())
+									.fg(Color::Red)
+							))
+							.with_color(Color::Red),
+					),
+				SimpleReason::Unexpected => report
 					.with_message(format!(
 						"{}, expected {}",
 						if e.found().is_some() {
@@ -85,7 +199,7 @@ fn main() {
 							))
 							.with_color(Color::Red),
 					),
-				chumsky::error::SimpleReason::Custom(msg) => {
+				SimpleReason::Custom(msg) => {
 					report.with_message(msg).with_label(
 						Label::new(e.span())
 							.with_message(format!("{}", msg.fg(Color::Red)))
@@ -97,3 +211,27 @@ fn main() {
 			report.finish().print(Source::from(&src)).unwrap();
 		});
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn errs_for(src: &str) -> Vec<Simple<char>> {
+		lexer().parse_recovery(src).1
+	}
+
+	#[test]
+	fn unclosed_while_block_is_detected_for_repl_continuation() {
+		assert!(unclosed_block(&errs_for("while true {")));
+	}
+
+	#[test]
+	fn unclosed_fn_block_is_detected_for_repl_continuation() {
+		assert!(unclosed_block(&errs_for("fn add(a, b) {")));
+	}
+
+	#[test]
+	fn a_complete_program_is_not_reported_as_unclosed() {
+		assert!(!unclosed_block(&errs_for("while true { break }")));
+	}
+}
@@ -0,0 +1,222 @@
+use crate::{Node, Spanned};
+use chumsky::error::Simple;
+use std::collections::HashMap;
+
+/// The inferred shape of a value, used only at typecheck time — `eval`
+/// and the VM still work in terms of [`crate::Value`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+	Number,
+	String,
+	Boolean,
+	Range,
+	Null,
+	/// The type of a call result, or anything we chose not to narrow.
+	Unknown,
+}
+
+impl std::fmt::Display for Type {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let name = match self {
+			Type::Number => "number",
+			Type::String => "string",
+			Type::Boolean => "boolean",
+			Type::Range => "range",
+			Type::Null => "null",
+			Type::Unknown => "unknown",
+		};
+
+		write!(f, "{}", name)
+	}
+}
+
+#[derive(Default, Clone)]
+struct Scope {
+	variables: HashMap<String, Type>,
+}
+
+/// Walk the AST once, before `eval`/the VM ever run it, inferring a
+/// [`Type`] for every node. Mismatches are collected as the same
+/// `Simple<char>` diagnostics the parser produces, so a caller can feed
+/// them straight into the existing `ariadne` report pipeline instead of
+/// the program crashing mid-run.
+pub fn typecheck(nodes: &[Spanned]) -> Vec<Simple<char>> {
+	typecheck_with(nodes, &mut HashMap::new())
+}
+
+/// Same as [`typecheck`], but seeded from and fed back into a caller-held
+/// variable scope, so a REPL can carry inferred types across inputs
+/// instead of starting from an empty scope every time.
+pub fn typecheck_with(
+	nodes: &[Spanned],
+	variables: &mut HashMap<String, Type>,
+) -> Vec<Simple<char>> {
+	let mut scope = Scope { variables: std::mem::take(variables) };
+	let mut errors = Vec::new();
+
+	for node in nodes {
+		check_node(node, &mut scope, &mut errors);
+	}
+
+	*variables = scope.variables;
+
+	errors
+}
+
+fn check_node(node: &Spanned, scope: &mut Scope, errors: &mut Vec<Simple<char>>) -> Type {
+	match &node.0 {
+		Node::NumericLiteral(_) => Type::Number,
+		Node::StringLiteral(_) => Type::String,
+		Node::BooleanLiteral(_) => Type::Boolean,
+		Node::Range(_, _) => Type::Range,
+		Node::Ident(name) => match scope.variables.get(name) {
+			Some(ty) => *ty,
+			None => {
+				errors.push(Simple::custom(
+					node.1.clone(),
+					format!("undefined variable `{}`", name),
+				));
+				Type::Unknown
+			}
+		},
+		Node::Variable(name, value) => {
+			let ty = check_node(value, scope, errors);
+			scope.variables.insert(name.clone(), ty);
+			Type::Null
+		}
+		Node::Binary(op, lhs, rhs) => {
+			let lhs_ty = check_node(lhs, scope, errors);
+			let rhs_ty = check_node(rhs, scope, errors);
+			check_binary(op, lhs_ty, rhs_ty, node, errors)
+		}
+		Node::While(cond, body) => {
+			let cond_ty = check_node(cond, scope, errors);
+
+			// Must match `truthy()` in lib.rs, which also accepts
+			// numbers, ranges, and strings as loop conditions.
+			if !matches!(
+				cond_ty,
+				Type::Boolean | Type::Number | Type::Range | Type::String | Type::Unknown
+			) {
+				errors.push(Simple::custom(
+					cond.1.clone(),
+					format!(
+						"expected a boolean, number, range, or string condition, found {}",
+						cond_ty
+					),
+				));
+			}
+
+			for stmt in body {
+				check_node(stmt, scope, errors);
+			}
+
+			Type::Null
+		}
+		Node::Call(_, args) => {
+			for arg in args {
+				check_node(arg, scope, errors);
+			}
+
+			// Native and user functions aren't statically signed, so the
+			// result of a call is simply unknown until it runs.
+			Type::Unknown
+		}
+		Node::Function(_, params, body) => {
+			let mut fn_scope = scope.clone();
+
+			for param in params {
+				fn_scope.variables.insert(param.clone(), Type::Unknown);
+			}
+
+			for stmt in body {
+				check_node(stmt, &mut fn_scope, errors);
+			}
+
+			Type::Null
+		}
+		Node::Return(value) => check_node(value, scope, errors),
+		Node::Break | Node::Continue => Type::Null,
+	}
+}
+
+fn check_binary(
+	op: &str,
+	lhs: Type,
+	rhs: Type,
+	node: &Spanned,
+	errors: &mut Vec<Simple<char>>,
+) -> Type {
+	if lhs == Type::Unknown || rhs == Type::Unknown {
+		return Type::Unknown;
+	}
+
+	match op {
+		"+" | "-" | "*" | "/" | "%" => {
+			if lhs != Type::Number || rhs != Type::Number {
+				errors.push(Simple::custom(
+					node.1.clone(),
+					format!(
+						"operator `{}` expects two numbers, found {} and {}",
+						op, lhs, rhs
+					),
+				));
+				Type::Unknown
+			} else {
+				Type::Number
+			}
+		}
+		"<" | "<=" | ">" | ">=" => {
+			if lhs != Type::Number || rhs != Type::Number {
+				errors.push(Simple::custom(
+					node.1.clone(),
+					format!(
+						"operator `{}` expects two numbers, found {} and {}",
+						op, lhs, rhs
+					),
+				));
+				Type::Unknown
+			} else {
+				Type::Boolean
+			}
+		}
+		// `==`/`!=` are defined for any pair of like or unlike values.
+		_ => Type::Boolean,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use chumsky::Parser;
+
+	fn errors_for(src: &str) -> Vec<Simple<char>> {
+		let ast = crate::lexer().parse(src).expect("parse error");
+		typecheck(&ast)
+	}
+
+	#[test]
+	fn flags_arithmetic_on_a_string() {
+		assert!(!errors_for("`a` + 1").is_empty());
+	}
+
+	#[test]
+	fn allows_string_while_condition() {
+		assert!(errors_for("while `a` { break }").is_empty());
+	}
+
+	#[test]
+	fn flags_undefined_variable() {
+		assert!(!errors_for("missing").is_empty());
+	}
+
+	#[test]
+	fn typecheck_with_seeds_from_caller_scope() {
+		let mut scope = HashMap::new();
+		scope.insert("x".to_string(), Type::Number);
+
+		let ast = crate::lexer().parse("x + 1").expect("parse error");
+
+		assert!(typecheck_with(&ast, &mut scope).is_empty());
+	}
+}
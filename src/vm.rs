@@ -0,0 +1,156 @@
+use crate::{
+	compiler::{Chunk, Instr},
+	env::standard_env,
+	Env, EvaluationError, SpannedEvaluationError, Value,
+};
+
+/// Executes a [`Chunk`] on an operand stack, keeping variables and
+/// natives in the same [`Env`] that the tree-walking `eval` uses. A
+/// single `loop` over an instruction pointer replaces the recursive
+/// descent, so long-running `while` loops no longer re-traverse the AST
+/// on every iteration.
+pub struct Vm {
+	pub env: Env,
+}
+
+impl Vm {
+	pub fn new() -> Self {
+		Vm { env: standard_env() }
+	}
+
+	pub fn with_env(env: Env) -> Self {
+		Vm { env }
+	}
+
+	pub fn run(&mut self, chunk: &Chunk) -> Result<Value, SpannedEvaluationError> {
+		let mut stack: Vec<Value> = Vec::new();
+		let mut ip: usize = 0;
+
+		while ip < chunk.instrs.len() {
+			let span = chunk.spans[ip].clone();
+
+			match &chunk.instrs[ip] {
+				Instr::NumPush(n) => stack.push(Value::Number(*n)),
+				Instr::StrPush(s) => stack.push(Value::String(s.clone())),
+				Instr::BoolPush(b) => stack.push(Value::Boolean(*b)),
+				Instr::RangePush(start, end) => {
+					stack.push(Value::Range(*start, *end))
+				}
+				Instr::Get(name) => {
+					let value = self.env.variables.get(name).cloned().ok_or((
+						EvaluationError::VariableNotFound(name.clone()),
+						span.clone(),
+					))?;
+					stack.push(value);
+				}
+				Instr::Set(name) => {
+					let value = stack.pop().unwrap_or(Value::Null);
+					self.env.variables.insert(name.clone(), value.clone());
+					stack.push(value);
+				}
+				Instr::Call(name, argc) => {
+					let native = *self.env.natives.get(name).ok_or((
+						EvaluationError::FunctionNotFound(name.clone()),
+						span.clone(),
+					))?;
+
+					let mut args: Vec<Value> = (0..*argc)
+						.map(|_| stack.pop().unwrap_or(Value::Null))
+						.collect();
+					args.reverse();
+
+					stack.push(
+						native(&mut self.env, args)
+							.map_err(|e| (e, span.clone()))?,
+					);
+				}
+				Instr::Jump(target) => {
+					ip = *target;
+					continue;
+				}
+				Instr::JumpIfFalse(target) => {
+					let value = stack.pop().unwrap_or(Value::Null);
+					if !truthy(&value) {
+						ip = *target;
+						continue;
+					}
+				}
+				Instr::Pop => {
+					stack.pop();
+				}
+			}
+
+			ip += 1;
+		}
+
+		Ok(stack.pop().unwrap_or(Value::Null))
+	}
+}
+
+fn truthy(value: &Value) -> bool {
+	match value {
+		Value::Boolean(b) => *b,
+		Value::Number(n) => *n > 0.0,
+		Value::Range(start, end) => start < end,
+		Value::String(s) => !s.is_empty(),
+		Value::Null => false,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::compiler::compile;
+	use chumsky::Parser;
+
+	fn run(src: &str) -> Value {
+		let ast = crate::lexer().parse(src).expect("parse error");
+		Vm::new().run(&compile(&ast)).expect("runtime error")
+	}
+
+	#[test]
+	fn evaluates_arithmetic_with_precedence() {
+		assert_eq!(run("1 + 2 * 3"), Value::Number(7.0));
+	}
+
+	#[test]
+	fn while_loop_stops_on_break() {
+		let result = run(
+			"count := 0
+			while 0..10 {
+				count := count + 1;
+				break
+			}
+			count",
+		);
+
+		assert_eq!(result, Value::Number(1.0));
+	}
+
+	#[test]
+	fn while_loop_continue_skips_rest_of_body() {
+		let result = run(
+			"total := 0
+			while 0..5 {
+				continue;
+				total := total + 100
+			}
+			total",
+		);
+
+		assert_eq!(result, Value::Number(0.0));
+	}
+
+	#[test]
+	fn variables_persist_across_chunks_on_the_same_vm() {
+		let mut vm = Vm::new();
+
+		let first = crate::lexer().parse("x := 41").unwrap();
+		vm.run(&compile(&first)).unwrap();
+
+		let second = crate::lexer().parse("x + 1").unwrap();
+		let result = vm.run(&compile(&second)).unwrap();
+
+		assert_eq!(result, Value::Number(42.0));
+	}
+}